@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The two fitted parameters behind the time model: how much to scale the
+/// naive `distance / feedrate` laser estimate, and how many seconds each
+/// layer change actually costs. Defaults match the hardcoded guesses the
+/// model used before `moai-time calibrate` existed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Calibration {
+    pub feedrate_scale: f64,
+    pub layer_change_seconds: f64,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            feedrate_scale: 1.0,
+            layer_change_seconds: 9.5,
+        }
+    }
+}
+
+fn config_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("moai-time.toml")
+}
+
+/// Loads the persisted calibration, falling back to the defaults if no
+/// config file exists yet or it can't be parsed.
+pub fn load() -> Calibration {
+    match fs::read_to_string(config_path()) {
+        Ok(contents) => toml::from_str(&contents).unwrap_or_default(),
+        Err(_) => Calibration::default(),
+    }
+}
+
+pub fn save(calibration: &Calibration) -> io::Result<()> {
+    let path = config_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents =
+        toml::to_string_pretty(calibration).expect("failed to serialize calibration as TOML");
+    fs::write(path, contents)
+}
@@ -0,0 +1,265 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const HEADER: &str = "timestamp,filename,content_hash,slicer_estimated_seconds,raw_laser_seconds,layer_count,model_estimated_seconds,actual_seconds";
+
+/// One row of the opt-in history log: what a run estimated, and (once
+/// recorded) what the print actually took.
+#[derive(Debug, Clone)]
+pub struct HistoryRecord {
+    pub timestamp: DateTime<Utc>,
+    pub filename: String,
+    pub content_hash: String,
+    pub slicer_estimated_seconds: Option<f64>,
+    pub raw_laser_seconds: f64,
+    pub layer_count: usize,
+    pub model_estimated_seconds: f64,
+    pub actual_seconds: Option<f64>,
+}
+
+fn history_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("moai-time")
+        .join("history.csv")
+}
+
+// FNV-1a 64-bit. Used instead of `DefaultHasher` because this hash is
+// persisted to disk as the join key across separate process invocations;
+// `DefaultHasher`'s algorithm isn't guaranteed stable across std releases,
+// while FNV-1a's is fixed and reproducible forever.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A non-cryptographic but *stable* fingerprint of the gcode's contents,
+/// good enough to recognize the same file across re-slices and re-runs.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash = FNV_OFFSET_BASIS;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    format!("{:016x}", hash)
+}
+
+fn field(s: &str) -> Option<f64> {
+    if s.is_empty() {
+        None
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Quotes a CSV field if it contains a comma, quote, or newline (doubling any
+/// embedded quotes), so free-text fields like `filename` can't be confused
+/// with column separators.
+fn csv_escape(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') || s.contains('\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_row(file: &mut impl Write, fields: &[String]) -> io::Result<()> {
+    let escaped: Vec<String> = fields.iter().map(|f| csv_escape(f)).collect();
+    writeln!(file, "{}", escaped.join(","))
+}
+
+/// Splits the whole file into CSV records, honoring `"..."` quoting so a
+/// quoted field that itself contains a newline (a legal filename character
+/// on Linux) doesn't get cut in half. A bare `\r` is dropped so CRLF-written
+/// files still line up with `parse_csv_line`.
+///
+/// Counting quote characters works here even though it doesn't special-case
+/// the `""` escape: each escaped quote contributes two quote chars, so the
+/// running parity (in/out of a quoted field) comes out the same either way.
+fn split_csv_records(contents: &str) -> Vec<String> {
+    let mut records = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in contents.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            '\n' if !in_quotes => records.push(std::mem::take(&mut current)),
+            '\r' if !in_quotes => {}
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        records.push(current);
+    }
+    records
+}
+
+/// Splits one CSV line into fields, honoring `"..."` quoting with `""` as an
+/// escaped quote. Unlike a plain `.split(',')`, this won't be fooled by a
+/// comma embedded in a quoted field.
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    current.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                current.push(c);
+            }
+        } else {
+            match c {
+                '"' => in_quotes = true,
+                ',' => fields.push(std::mem::take(&mut current)),
+                _ => current.push(c),
+            }
+        }
+    }
+    fields.push(current);
+    fields
+}
+
+fn record_to_row(record: &HistoryRecord) -> Vec<String> {
+    vec![
+        record.timestamp.to_rfc3339(),
+        record.filename.clone(),
+        record.content_hash.clone(),
+        record
+            .slicer_estimated_seconds
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+        record.raw_laser_seconds.to_string(),
+        record.layer_count.to_string(),
+        record.model_estimated_seconds.to_string(),
+        record
+            .actual_seconds
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+    ]
+}
+
+pub fn append(record: &HistoryRecord) -> io::Result<()> {
+    let path = history_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let is_new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if is_new {
+        writeln!(file, "{}", HEADER)?;
+    }
+    write_row(&mut file, &record_to_row(record))
+}
+
+pub fn load() -> io::Result<Vec<HistoryRecord>> {
+    let path = history_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut records = Vec::new();
+    for line in split_csv_records(&contents).into_iter().skip(1) {
+        let parts = parse_csv_line(&line);
+        if parts.len() != 8 {
+            continue;
+        }
+        records.push(HistoryRecord {
+            timestamp: DateTime::parse_from_rfc3339(&parts[0])
+                .map(|d| d.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            filename: parts[1].clone(),
+            content_hash: parts[2].clone(),
+            slicer_estimated_seconds: field(&parts[3]),
+            raw_laser_seconds: parts[4].parse().unwrap_or(0.0),
+            layer_count: parts[5].parse().unwrap_or(0),
+            model_estimated_seconds: parts[6].parse().unwrap_or(0.0),
+            actual_seconds: field(&parts[7]),
+        });
+    }
+    Ok(records)
+}
+
+/// Records a later-observed actual print duration against every history row
+/// sharing `content_hash`, so `calibrate` can draw on it automatically.
+pub fn record_actual(content_hash: &str, actual_seconds: f64) -> io::Result<usize> {
+    let mut records = load()?;
+    let mut updated = 0;
+    for record in records.iter_mut() {
+        if record.content_hash == content_hash {
+            record.actual_seconds = Some(actual_seconds);
+            updated += 1;
+        }
+    }
+
+    let path = history_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::File::create(path)?;
+    writeln!(file, "{}", HEADER)?;
+    for record in &records {
+        write_row(&mut file, &record_to_row(record))?;
+    }
+    Ok(updated)
+}
+
+/// Prints, per content hash, how the estimate has changed across re-slices
+/// and the average ratio between the slicer's own estimate and the model's.
+pub fn print_history() -> io::Result<()> {
+    let records = load()?;
+    let mut by_hash: HashMap<&str, Vec<&HistoryRecord>> = HashMap::new();
+    for record in &records {
+        by_hash.entry(&record.content_hash).or_default().push(record);
+    }
+
+    if by_hash.is_empty() {
+        println!("No history recorded yet.");
+        return Ok(());
+    }
+
+    for (hash, mut entries) in by_hash {
+        entries.sort_by_key(|r| r.timestamp);
+        let filename = entries.last().map(|r| r.filename.as_str()).unwrap_or("?");
+        println!("{} ({})", hash, filename);
+        for entry in &entries {
+            print!(
+                "\t{}: model {:.1}s",
+                entry.timestamp.to_rfc3339(),
+                entry.model_estimated_seconds
+            );
+            if let Some(slicer) = entry.slicer_estimated_seconds {
+                print!(", slicer {:.1}s", slicer);
+            }
+            if let Some(actual) = entry.actual_seconds {
+                print!(", actual {:.1}s", actual);
+            }
+            println!();
+        }
+
+        let ratios: Vec<f64> = entries
+            .iter()
+            .filter_map(|r| {
+                r.slicer_estimated_seconds
+                    .map(|slicer| slicer / r.model_estimated_seconds)
+            })
+            .collect();
+        if !ratios.is_empty() {
+            let avg_ratio = ratios.iter().sum::<f64>() / (ratios.len() as f64);
+            println!("\taverage slicer/model ratio: {:.3}", avg_ratio);
+        }
+    }
+    Ok(())
+}
@@ -1,58 +1,116 @@
-use clap::{App, Arg};
+mod config;
+mod history;
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use clap::{App, AppSettings, Arg, SubCommand};
+use config::Calibration;
+use flate2::read::GzDecoder;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
 use std::fmt;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Result};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Result};
 use std::time::Duration;
 use uom::si::f64::*;
 use uom::si::length::millimeter;
 use uom::si::time::second;
 use uom::si::velocity::{micrometer_per_second, millimeter_per_second};
 
-#[derive(Debug, Default)]
+/// A single G0/G1 move, kept around (in addition to the running distance and
+/// time totals) so the trapezoidal accel model can do its two-pass sweep
+/// over a layer's moves after the fact.
+#[derive(Debug, Clone, Copy, Default)]
+struct MoveRecord {
+    distance_mm: f64,
+    feedrate_mm_s: f64,
+    delta_x_mm: f64,
+    delta_y_mm: f64,
+}
+
+#[derive(Debug, Default, Clone)]
 struct GcodeLineInfo {
     distance: Length,
     time: Time,
+    moves: Vec<MoveRecord>,
 }
 
 #[derive(Debug, Default)]
 struct GcodeInfo {
     slicer_estimated_duration: Option<Duration>,
     layers: Vec<GcodeLineInfo>,
+    /// The real layer number of `layers[0]`, so a `--start-layer`-restricted
+    /// `GcodeInfo` can still report accurate `layer_index` values.
+    layer_index_offset: usize,
 }
 
 impl GcodeInfo {
-    fn layer_change_time(&self) -> Duration {
-        let t: Time = Time::new::<second>(9.5) * (self.layers.len() as f64);
-        let secs = t.get::<second>();
-        Duration::new(secs.floor() as u64, (secs.fract() * 1_000_000.0).floor() as u32)
+    /// The uncalibrated laser time (naive `distance / feedrate`, or the
+    /// trapezoidal model's time once `apply_trapezoidal_model` has run),
+    /// before applying a calibration's `feedrate_scale`.
+    fn laser_time_raw(&self) -> Duration {
+        let t: Time = self.layers.iter().map(|l| l.time).sum();
+        time_to_duration(t)
     }
 
-    fn laser_time(&self) -> Duration {
-        let t: Time = self.layers.iter().map(|l| l.time).sum();
-        let secs = t.get::<second>();
-        Duration::new(secs.floor() as u64, (secs.fract() * 1_000_000.0).floor() as u32)
+    fn laser_time(&self, calibration: &Calibration) -> Duration {
+        let secs = self.laser_time_raw().as_secs_f64() * calibration.feedrate_scale;
+        Duration::from_secs_f64(secs.max(0.0))
+    }
+
+    fn layer_change_time(&self, calibration: &Calibration) -> Duration {
+        let secs = calibration.layer_change_seconds * (self.layers.len() as f64);
+        Duration::from_secs_f64(secs.max(0.0))
     }
 
-    fn total_time(&self) -> Duration {
-        self.layer_change_time() + self.laser_time()
+    fn total_time(&self, calibration: &Calibration) -> Duration {
+        self.layer_change_time(calibration) + self.laser_time(calibration)
     }
 }
 
-fn parse_file(file: File) -> Result<GcodeInfo> {
+fn time_to_duration(t: Time) -> Duration {
+    Duration::from_secs_f64(t.get::<second>().max(0.0))
+}
+
+/// Opens `filename` for reading, returning a buffered reader plus a byte
+/// length hint for the progress bar (`None` when the length can't be known
+/// up front, e.g. stdin or a gzip stream). A filename of `-` reads from
+/// stdin; `.gz` files are transparently decompressed.
+fn open_input(filename: &str) -> Result<(Box<dyn BufRead>, Option<u64>)> {
+    if filename == "-" {
+        Ok((Box::new(BufReader::new(io::stdin())), None))
+    } else if filename.ends_with(".gz") {
+        let file = File::open(filename)?;
+        Ok((Box::new(BufReader::new(GzDecoder::new(file))), None))
+    } else {
+        let file = File::open(filename)?;
+        let length = file.metadata()?.len();
+        Ok((Box::new(BufReader::new(file)), Some(length)))
+    }
+}
+
+fn parse_file(reader: impl BufRead, length_hint: Option<u64>) -> Result<GcodeInfo> {
     let mut gcode_info: GcodeInfo = Default::default();
     let mut current_x: f64 = 0.0;
     let mut current_y: f64 = 0.0;
     let mut current_feedrate: Velocity = Velocity::new::<millimeter_per_second>(0.0);
     let mut current_layer: Option<usize> = None;
 
-    let progress_bar = ProgressBar::new(file.metadata()?.len());
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{msg} [{wide_bar}] [{elapsed_precise}] {bytes}/{total_bytes} (ETA: {eta})"),
-    );
+    let progress_bar = match length_hint {
+        Some(length) => {
+            let bar = ProgressBar::new(length);
+            bar.set_style(ProgressStyle::default_bar().template(
+                "{msg} [{wide_bar}] [{elapsed_precise}] {bytes}/{total_bytes} (ETA: {eta})",
+            ));
+            bar
+        }
+        None => {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(ProgressStyle::default_spinner().template("{msg} [{elapsed_precise}] {bytes} read"));
+            bar
+        }
+    };
     progress_bar.set_message("Reading gcode lines");
-    let reader = BufReader::new(progress_bar.wrap_read(file));
+    let reader = BufReader::new(progress_bar.wrap_read(reader));
 
     for line in reader.lines() {
         let line = line?;
@@ -112,12 +170,104 @@ fn parse_file(file: File) -> Result<GcodeInfo> {
             };
             layer_info.distance += this_distance;
             layer_info.time += this_time;
+            layer_info.moves.push(MoveRecord {
+                distance_mm: this_distance.get::<millimeter>(),
+                feedrate_mm_s: current_feedrate.get::<millimeter_per_second>(),
+                delta_x_mm: delta_x.get::<millimeter>(),
+                delta_y_mm: delta_y.get::<millimeter>(),
+            });
         }
     }
 
     Ok(gcode_info)
 }
 
+/// The default junction deviation, in millimeters, used by the trapezoidal
+/// accel model. This mirrors the values firmwares like Marlin ship with;
+/// smaller values force more aggressive slowdowns on sharp corners.
+const JUNCTION_DEVIATION_MM: f64 = 0.01;
+
+/// Replaces each layer's naive `distance / feedrate` time with one computed
+/// from a trapezoidal velocity profile: a two-pass (forward, then backward)
+/// sweep makes each move's entry/exit speed consistent with what `accel_mm_s2`
+/// can actually achieve over the move's distance, then each move's time is
+/// the accelerate/cruise/decelerate (or triangular, if it never reaches
+/// cruise) profile through that distance.
+fn apply_trapezoidal_model(gcode_info: &mut GcodeInfo, accel_mm_s2: f64) {
+    for layer in gcode_info.layers.iter_mut() {
+        layer.time = trapezoidal_layer_time(&layer.moves, accel_mm_s2);
+    }
+}
+
+fn trapezoidal_layer_time(moves: &[MoveRecord], accel_mm_s2: f64) -> Time {
+    let n = moves.len();
+    if n == 0 {
+        return Time::new::<second>(0.0);
+    }
+
+    // junction_speed[i] is the speed (mm/s) at the junction before move i,
+    // for i in 0..=n; junction_speed[0] and junction_speed[n] stay at rest.
+    let mut junction_speed = vec![0.0_f64; n + 1];
+
+    for i in 1..n {
+        let prev = &moves[i - 1];
+        let curr = &moves[i];
+        let prev_len = prev.distance_mm;
+        let curr_len = curr.distance_mm;
+        if prev_len < f64::EPSILON || curr_len < f64::EPSILON {
+            continue;
+        }
+        let vf = prev.feedrate_mm_s.min(curr.feedrate_mm_s);
+        let (ux_in, uy_in) = (prev.delta_x_mm / prev_len, prev.delta_y_mm / prev_len);
+        let (ux_out, uy_out) = (curr.delta_x_mm / curr_len, curr.delta_y_mm / curr_len);
+        // The bend angle at the joint: pi for a straight line (no slowdown
+        // needed), 0 for a full reversal (must stop).
+        let cos_bend = (-(ux_in * ux_out + uy_in * uy_out)).clamp(-1.0, 1.0);
+        let half_sin = (cos_bend.acos() / 2.0).sin();
+        junction_speed[i] = if (1.0 - half_sin).abs() < f64::EPSILON {
+            vf
+        } else {
+            (accel_mm_s2 * JUNCTION_DEVIATION_MM * half_sin / (1.0 - half_sin))
+                .sqrt()
+                .min(vf)
+        };
+    }
+
+    // Forward pass: a junction speed can't exceed what's reachable
+    // accelerating from the previous junction over that move's distance.
+    for i in 1..=n {
+        let reachable = (junction_speed[i - 1].powi(2) + 2.0 * accel_mm_s2 * moves[i - 1].distance_mm).sqrt();
+        junction_speed[i] = junction_speed[i].min(reachable);
+    }
+    // Backward pass: a junction speed also can't exceed what the move ahead
+    // of it can decelerate down from, to hit the next junction's speed.
+    for i in (0..n).rev() {
+        let reachable = (junction_speed[i + 1].powi(2) + 2.0 * accel_mm_s2 * moves[i].distance_mm).sqrt();
+        junction_speed[i] = junction_speed[i].min(reachable);
+    }
+
+    let mut total_secs = 0.0;
+    for (i, mv) in moves.iter().enumerate() {
+        let vf = mv.feedrate_mm_s;
+        let d = mv.distance_mm;
+        if vf < f64::EPSILON || d < f64::EPSILON {
+            continue;
+        }
+        let v0 = junction_speed[i];
+        let v1 = junction_speed[i + 1];
+        let d_acc = ((vf * vf - v0 * v0) / (2.0 * accel_mm_s2)).max(0.0);
+        let d_dec = ((vf * vf - v1 * v1) / (2.0 * accel_mm_s2)).max(0.0);
+        total_secs += if d_acc + d_dec <= d {
+            (vf - v0) / accel_mm_s2 + (d - d_acc - d_dec) / vf + (vf - v1) / accel_mm_s2
+        } else {
+            let vp = (((2.0 * accel_mm_s2 * d) + v0 * v0 + v1 * v1) / 2.0).sqrt();
+            (vp - v0) / accel_mm_s2 + (vp - v1) / accel_mm_s2
+        };
+    }
+
+    Time::new::<second>(total_secs)
+}
+
 struct PrettyDuration(Duration);
 
 impl fmt::Display for PrettyDuration {
@@ -173,11 +323,272 @@ impl fmt::Display for PrettyDuration {
     }
 }
 
+/// Prints one CSV row per layer (distance, laser time, layer-change
+/// overhead, running total, and derived feedrate/throughput), followed by a
+/// trailing summary row so the whole job can still be seen at a glance.
+fn print_csv(filename: &str, parsed: &GcodeInfo, calibration: &Calibration) {
+    println!(
+        "file,layer_index,distance_mm,laser_seconds,layer_change_seconds,cumulative_seconds,avg_feedrate_mm_s,throughput_mm_per_s"
+    );
+    let layer_change_secs = calibration.layer_change_seconds;
+    let mut cumulative_secs = 0.0;
+    let mut total_distance_mm = 0.0;
+    for (i, layer) in parsed.layers.iter().enumerate() {
+        let layer_index = parsed.layer_index_offset + i;
+        let distance_mm = layer.distance.get::<millimeter>();
+        let laser_secs = layer.time.get::<second>() * calibration.feedrate_scale;
+        cumulative_secs += laser_secs + layer_change_secs;
+        total_distance_mm += distance_mm;
+        let avg_feedrate_mm_s = if laser_secs > 0.0 {
+            distance_mm / laser_secs
+        } else {
+            0.0
+        };
+        let throughput_mm_per_s = if laser_secs + layer_change_secs > 0.0 {
+            distance_mm / (laser_secs + layer_change_secs)
+        } else {
+            0.0
+        };
+        println!(
+            "{},{},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}",
+            filename,
+            layer_index,
+            distance_mm,
+            laser_secs,
+            layer_change_secs,
+            cumulative_secs,
+            avg_feedrate_mm_s,
+            throughput_mm_per_s,
+        );
+    }
+    let total_laser_secs = parsed.laser_time(calibration).as_secs_f64();
+    let total_layer_change_secs = parsed.layer_change_time(calibration).as_secs_f64();
+    let total_secs = parsed.total_time(calibration).as_secs_f64();
+    let avg_feedrate_mm_s = if total_laser_secs > 0.0 {
+        total_distance_mm / total_laser_secs
+    } else {
+        0.0
+    };
+    let throughput_mm_per_s = if total_secs > 0.0 {
+        total_distance_mm / total_secs
+    } else {
+        0.0
+    };
+    println!(
+        "{},TOTAL,{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}",
+        filename,
+        total_distance_mm,
+        total_laser_secs,
+        total_layer_change_secs,
+        total_secs,
+        avg_feedrate_mm_s,
+        throughput_mm_per_s,
+    );
+}
+
+#[derive(Serialize)]
+struct JsonLayerInfo {
+    layer_index: usize,
+    distance_mm: f64,
+    laser_seconds: f64,
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    file: String,
+    slicer_estimated_seconds: Option<f64>,
+    laser_seconds: f64,
+    layer_change_seconds: f64,
+    total_seconds: f64,
+    projected_finish: String,
+    layers: Vec<JsonLayerInfo>,
+}
+
+/// Serializes the full `GcodeInfo`, plus an RFC3339 `projected_finish`
+/// timestamp computed as `start_at + total_time()` (defaulting `start_at`
+/// to now), so the output can be piped straight into a print queue.
+fn print_json(
+    filename: &str,
+    parsed: &GcodeInfo,
+    calibration: &Calibration,
+    start_at: DateTime<Utc>,
+) {
+    let total_time = parsed.total_time(calibration);
+    let projected_finish =
+        start_at + ChronoDuration::from_std(total_time).unwrap_or_else(|_| ChronoDuration::zero());
+
+    let output = JsonOutput {
+        file: filename.to_string(),
+        slicer_estimated_seconds: parsed.slicer_estimated_duration.map(|d| d.as_secs_f64()),
+        laser_seconds: parsed.laser_time(calibration).as_secs_f64(),
+        layer_change_seconds: parsed.layer_change_time(calibration).as_secs_f64(),
+        total_seconds: total_time.as_secs_f64(),
+        projected_finish: projected_finish.to_rfc3339(),
+        layers: parsed
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| JsonLayerInfo {
+                layer_index: parsed.layer_index_offset + i,
+                distance_mm: layer.distance.get::<millimeter>(),
+                laser_seconds: layer.time.get::<second>() * calibration.feedrate_scale,
+            })
+            .collect(),
+    };
+
+    println!(
+        "{}",
+        serde_json::to_string(&output).expect("failed to serialize GcodeInfo as JSON")
+    );
+}
+
+fn print_text(filename: &str, parsed: &GcodeInfo, calibration: &Calibration) {
+    let total_time = parsed.total_time(calibration);
+    println!("For {}:", filename);
+    if let Some(est_duration) = parsed.slicer_estimated_duration {
+        println!(
+            "\tSlicer estimated print time: {}",
+            PrettyDuration(est_duration)
+        );
+    }
+    println!(
+        "\tEstimated print time: \x1b[32;m{}\x1b[0m",
+        PrettyDuration(total_time)
+    );
+    println!(
+        "\t\t       Laser: {}",
+        PrettyDuration(parsed.laser_time(calibration))
+    );
+    println!(
+        "\t\tLayer change: {}",
+        PrettyDuration(parsed.layer_change_time(calibration))
+    );
+}
+
+/// Restricts `parsed` to the layers in `[start, end]` (inclusive, clamped to
+/// the available layers) so the existing report printers can be reused
+/// unchanged for a `--start-layer`/`--end-layer` partial-print estimate.
+fn slice_layers(parsed: &GcodeInfo, start: usize, end: usize) -> GcodeInfo {
+    let end = end.min(parsed.layers.len().saturating_sub(1));
+    let layers = if parsed.layers.is_empty() || start > end {
+        Vec::new()
+    } else {
+        parsed.layers[start..=end].to_vec()
+    };
+    GcodeInfo {
+        slicer_estimated_duration: None,
+        layers,
+        layer_index_offset: start,
+    }
+}
+
+/// Prints how long until the laser reaches the start of `target_layer`, by
+/// summing laser and layer-change time over every layer before it.
+fn print_time_to_layer(filename: &str, parsed: &GcodeInfo, calibration: &Calibration, target_layer: usize) {
+    let end = target_layer.min(parsed.layers.len());
+    let preceding = GcodeInfo {
+        slicer_estimated_duration: None,
+        layers: parsed.layers[..end].to_vec(),
+        layer_index_offset: 0,
+    };
+    println!(
+        "For {}: time to reach layer {}: {}",
+        filename,
+        target_layer,
+        PrettyDuration(preceding.total_time(calibration))
+    );
+}
+
+/// Fits `feedrate_scale` (k) and `layer_change_seconds` (c) by least squares
+/// over `actual_i = k * laser_i + c * layers_i`, and reports the residual
+/// standard deviation as a rough fit-quality indicator. Errors out rather
+/// than guessing if the samples' `(laser, layers)` pairs are collinear (or
+/// there's only one sample), since the 2x2 normal equations have no unique
+/// solution in that case.
+fn fit_calibration(samples: &[(f64, f64, f64)]) -> Result<(Calibration, f64)> {
+    let n = samples.len() as f64;
+    let mut sum_laser_sq = 0.0;
+    let mut sum_laser_layers = 0.0;
+    let mut sum_layers_sq = 0.0;
+    let mut sum_actual_laser = 0.0;
+    let mut sum_actual_layers = 0.0;
+
+    for &(laser, layers, actual) in samples {
+        sum_laser_sq += laser * laser;
+        sum_laser_layers += laser * layers;
+        sum_layers_sq += layers * layers;
+        sum_actual_laser += actual * laser;
+        sum_actual_layers += actual * layers;
+    }
+
+    // Solve [[sum_laser_sq, sum_laser_layers], [sum_laser_layers, sum_layers_sq]] * [k, c] = [sum_actual_laser, sum_actual_layers]
+    let det = sum_laser_sq * sum_layers_sq - sum_laser_layers * sum_laser_layers;
+    if samples.len() < 2 || det.abs() <= 1e-12 * sum_laser_sq * sum_layers_sq {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not fit a calibration: samples are too few or their (laser, layers) pairs are collinear (singular normal equations)",
+        ));
+    }
+    let k = (sum_actual_laser * sum_layers_sq - sum_actual_layers * sum_laser_layers) / det;
+    let c = (sum_laser_sq * sum_actual_layers - sum_laser_layers * sum_actual_laser) / det;
+
+    if k <= 0.0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "could not fit a calibration: feedrate_scale solved to a non-physical {:.6} \
+                 (samples likely too noisy or too few to constrain the fit)",
+                k
+            ),
+        ));
+    }
+
+    let residual_sum_sq: f64 = samples
+        .iter()
+        .map(|&(laser, layers, actual)| {
+            let predicted = k * laser + c * layers;
+            (actual - predicted).powi(2)
+        })
+        .sum();
+    let degrees_of_freedom = (n - 2.0).max(1.0);
+    let residual_stddev = (residual_sum_sq / degrees_of_freedom).sqrt();
+
+    Ok((
+        Calibration {
+            feedrate_scale: k,
+            layer_change_seconds: c,
+        },
+        residual_stddev,
+    ))
+}
+
+/// Fits a calibration from `(raw_laser_seconds, layer_count, actual_seconds)`
+/// triples, regardless of whether they came from `--sample` files parsed
+/// just now or from accumulated `moai-time history` rows.
+fn run_calibrate(fit_samples: &[(f64, f64, f64)]) -> Result<()> {
+    let (calibration, residual_stddev) = fit_calibration(fit_samples)?;
+    println!(
+        "Fitted feedrate_scale (k) = {:.6}, layer_change_seconds (c) = {:.6}",
+        calibration.feedrate_scale, calibration.layer_change_seconds
+    );
+    println!(
+        "Residual standard deviation: \u{b1}{:.2} seconds across {} samples",
+        residual_stddev,
+        fit_samples.len()
+    );
+
+    config::save(&calibration)?;
+    println!("Saved calibration to config for future estimation runs.");
+
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let matches = App::new("moai-time")
         .version("0.1")
         .author("Nick Meharry <nick@nickmeharry.com>")
         .about("More accurate time estimation for Peopoly Moai gcode files.")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("INPUT")
                 .help("Sets the input file to use")
@@ -185,29 +596,253 @@ fn main() -> Result<()> {
                 .multiple(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "csv", "json"])
+                .default_value("text")
+                .help("Sets the output format"),
+        )
+        .arg(
+            Arg::with_name("start-at")
+                .long("start-at")
+                .takes_value(true)
+                .help("Projects the finish time in --format json from this RFC3339 timestamp instead of now"),
+        )
+        .arg(
+            Arg::with_name("accel")
+                .long("accel")
+                .takes_value(true)
+                .help("Models laser moves with a trapezoidal velocity profile using this max acceleration, in mm/s^2, instead of an instantaneous feedrate"),
+        )
+        .arg(
+            Arg::with_name("start-layer")
+                .long("start-layer")
+                .takes_value(true)
+                .help("Restricts the reported estimate to layers at or after this layer number"),
+        )
+        .arg(
+            Arg::with_name("end-layer")
+                .long("end-layer")
+                .takes_value(true)
+                .help("Restricts the reported estimate to layers at or before this layer number"),
+        )
+        .arg(
+            Arg::with_name("time-to-layer")
+                .long("time-to-layer")
+                .takes_value(true)
+                .help("Prints how long until the laser reaches the start of this layer number, instead of the full report"),
+        )
+        .arg(
+            Arg::with_name("log-history")
+                .long("log-history")
+                .help("Opt in to appending this run's estimate to ~/.local/share/moai-time/history.csv"),
+        )
+        .subcommand(
+            SubCommand::with_name("calibrate")
+                .about("Fits layer-change overhead and a feedrate correction from real prints")
+                .arg(
+                    Arg::with_name("sample")
+                        .long("sample")
+                        .value_names(&["GCODE_FILE", "ACTUAL_SECONDS"])
+                        .number_of_values(2)
+                        .multiple(true)
+                        .help("A (gcode file, measured actual print duration in seconds) pair. Defaults to every history row with a recorded actual duration"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Shows recorded estimates per gcode file and their drift from the slicer's own estimate"),
+        )
+        .subcommand(
+            SubCommand::with_name("record-actual")
+                .about("Records an observed actual print duration against a gcode file's history entries")
+                .arg(
+                    Arg::with_name("GCODE_FILE")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("ACTUAL_SECONDS")
+                        .required(true)
+                        .index(2),
+                ),
+        )
         .get_matches();
 
-    for filename in matches.values_of("INPUT").unwrap() {
-        let f = File::open(filename)?;
-        let parsed = parse_file(f)?;
-        let total_time = parsed.total_time();
-        println!("For {}:", filename);
-        if let Some(est_duration) = parsed.slicer_estimated_duration {
-            println!(
-                "\tSlicer estimated print time: {}",
-                PrettyDuration(est_duration)
-            );
+    if let Some(calibrate_matches) = matches.subcommand_matches("calibrate") {
+        let fit_samples: Vec<(f64, f64, f64)> = match calibrate_matches.values_of("sample") {
+            Some(values) => {
+                let values: Vec<&str> = values.collect();
+                values
+                    .chunks(2)
+                    .map(|pair| {
+                        let (reader, length_hint) = open_input(pair[0])?;
+                        let parsed = parse_file(reader, length_hint)?;
+                        let actual_secs = pair[1]
+                            .parse::<f64>()
+                            .expect("ACTUAL_SECONDS must be a number");
+                        Ok((
+                            parsed.laser_time_raw().as_secs_f64(),
+                            parsed.layers.len() as f64,
+                            actual_secs,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+            None => history::load()?
+                .into_iter()
+                .filter_map(|record| {
+                    record
+                        .actual_seconds
+                        .map(|actual| (record.raw_laser_seconds, record.layer_count as f64, actual))
+                })
+                .collect(),
+        };
+        if fit_samples.is_empty() {
+            println!("No samples given and no history rows have a recorded actual duration yet.");
+            return Ok(());
         }
+        return run_calibrate(&fit_samples);
+    }
+
+    if matches.subcommand_matches("history").is_some() {
+        return history::print_history();
+    }
+
+    if let Some(record_matches) = matches.subcommand_matches("record-actual") {
+        let filename = record_matches.value_of("GCODE_FILE").unwrap();
+        let actual_secs = record_matches
+            .value_of("ACTUAL_SECONDS")
+            .unwrap()
+            .parse::<f64>()
+            .expect("ACTUAL_SECONDS must be a number");
+        let bytes = fs::read(filename)?;
+        let hash = history::content_hash(&bytes);
+        let updated = history::record_actual(&hash, actual_secs)?;
         println!(
-            "\tEstimated print time: \x1b[32;m{}\x1b[0m",
-            PrettyDuration(total_time)
-        );
-        println!("\t\t       Laser: {}", PrettyDuration(parsed.laser_time()));
-        println!(
-            "\t\tLayer change: {}",
-            PrettyDuration(parsed.layer_change_time())
+            "Recorded actual duration of {:.1}s against {} history row(s) for {}.",
+            actual_secs, updated, filename
         );
+        return Ok(());
+    }
+
+    let calibration = config::load();
+    let format = matches.value_of("format").unwrap();
+    let start_at = match matches.value_of("start-at") {
+        Some(s) => DateTime::parse_from_rfc3339(s)
+            .expect("--start-at must be an RFC3339 timestamp")
+            .with_timezone(&Utc),
+        None => Utc::now(),
+    };
+    let accel_mm_s2 = matches.value_of("accel").map(|s| {
+        let accel = s.parse::<f64>().expect("--accel must be a number");
+        if accel <= 0.0 {
+            panic!("--accel must be > 0");
+        }
+        accel
+    });
+    let start_layer = matches
+        .value_of("start-layer")
+        .map(|s| s.parse::<usize>().expect("--start-layer must be a layer number"));
+    let end_layer = matches
+        .value_of("end-layer")
+        .map(|s| s.parse::<usize>().expect("--end-layer must be a layer number"));
+    let time_to_layer = matches
+        .value_of("time-to-layer")
+        .map(|s| s.parse::<usize>().expect("--time-to-layer must be a layer number"));
+    let log_history = matches.is_present("log-history");
+
+    for filename in matches.values_of("INPUT").unwrap() {
+        let (reader, length_hint) = open_input(filename)?;
+        let mut parsed = parse_file(reader, length_hint)?;
+        if let Some(accel_mm_s2) = accel_mm_s2 {
+            apply_trapezoidal_model(&mut parsed, accel_mm_s2);
+        }
+
+        if log_history && filename != "-" {
+            let bytes = fs::read(filename)?;
+            history::append(&history::HistoryRecord {
+                timestamp: Utc::now(),
+                filename: filename.to_string(),
+                content_hash: history::content_hash(&bytes),
+                slicer_estimated_seconds: parsed.slicer_estimated_duration.map(|d| d.as_secs_f64()),
+                raw_laser_seconds: parsed.laser_time_raw().as_secs_f64(),
+                layer_count: parsed.layers.len(),
+                model_estimated_seconds: parsed.total_time(&calibration).as_secs_f64(),
+                actual_seconds: None,
+            })?;
+        }
+
+        if let Some(target_layer) = time_to_layer {
+            print_time_to_layer(filename, &parsed, &calibration, target_layer);
+            continue;
+        }
+
+        let sliced = if start_layer.is_some() || end_layer.is_some() {
+            Some(slice_layers(
+                &parsed,
+                start_layer.unwrap_or(0),
+                end_layer.unwrap_or_else(|| parsed.layers.len().saturating_sub(1)),
+            ))
+        } else {
+            None
+        };
+        let reported = sliced.as_ref().unwrap_or(&parsed);
+
+        match format {
+            "csv" => print_csv(filename, reported, &calibration),
+            "json" => print_json(filename, reported, &calibration, start_at),
+            _ => print_text(filename, reported, &calibration),
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_calibration_solves_exact_linear_model() {
+        // actual = 2.0 * laser + 3.0 * layers, exactly, so the least-squares
+        // fit should recover k = 2.0, c = 3.0 with zero residual.
+        let samples = [(1.0, 1.0, 5.0), (2.0, 1.0, 7.0), (1.0, 2.0, 8.0)];
+        let (calibration, residual_stddev) = fit_calibration(&samples).unwrap();
+        assert!((calibration.feedrate_scale - 2.0).abs() < 1e-9);
+        assert!((calibration.layer_change_seconds - 3.0).abs() < 1e-9);
+        assert!(residual_stddev < 1e-9);
+    }
+
+    #[test]
+    fn trapezoidal_layer_time_picks_cruise_profile_when_distance_allows() {
+        // accel = 100 mm/s^2, feedrate = 10 mm/s: reaching and leaving cruise
+        // speed each take 0.5 mm, so a 10 mm move has 9 mm left to cruise.
+        let moves = [MoveRecord {
+            distance_mm: 10.0,
+            feedrate_mm_s: 10.0,
+            delta_x_mm: 10.0,
+            delta_y_mm: 0.0,
+        }];
+        let time = trapezoidal_layer_time(&moves, 100.0);
+        assert!((time.get::<second>() - 1.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn trapezoidal_layer_time_picks_triangular_profile_when_distance_too_short() {
+        // Same accel/feedrate, but the move is shorter than the 1 mm it'd
+        // take to accelerate up to and back down from cruise speed, so it
+        // never reaches `feedrate_mm_s` and peaks at a lower speed instead.
+        let moves = [MoveRecord {
+            distance_mm: 0.5,
+            feedrate_mm_s: 10.0,
+            delta_x_mm: 0.5,
+            delta_y_mm: 0.0,
+        }];
+        let time = trapezoidal_layer_time(&moves, 100.0);
+        let expected = 2.0 * 50.0_f64.sqrt() / 100.0;
+        assert!((time.get::<second>() - expected).abs() < 1e-9);
+    }
+}